@@ -10,45 +10,27 @@ pub fn shape(
     _num_features: u32,
 ) -> i32 {
     let font = Font::from_ref(font_ref);
-    let mut buffer = GlyphBuffer::from_ref(buf_ref);
-
-    let mut out = vec![];
+    let features = parse_features(_features, _num_features);
 
-    let mut digits = vec![];
-    for item in buffer.glyphs.iter() {
-        if let Some(number) = unicode_codepoint_to_number(item.codepoint) {
-            digits.push(number);
-        } else {
-            // process digits, add them as roman numerals instead of the actual glyphs
-            process_digits(&mut digits, &mut out);
-
-            out.push(*item);
-        }
-    }
+    // Work out which digit runs we will override, and with what, from the
+    // original unicode buffer before anything touches it. The handle writes its
+    // (unchanged) contents back on drop, so it is dropped straight away rather
+    // than after the shaping below, where it would overwrite the shaped glyphs.
+    let runs = {
+        let buffer = GlyphBuffer::from_ref(buf_ref);
+        collect_numeral_runs(&font, &buffer.glyphs, &features)
+    };
 
-    // also handle non empty digits here, otherwise numbers at the end of the string won't work
-    process_digits(&mut digits, &mut out);
-
-    // fix characters
-    for item in out.iter_mut() {
-        let is_overline = item.codepoint == 0x305;
-
-        // Map character to glyph
-        item.codepoint = font.get_glyph(item.codepoint, 0);
-
-        // Set advance
-        item.x_advance = if is_overline {
-            // overline doesn't move forward,
-            // since we want the next character at the same spot
-            0
-        } else {
-            font.get_glyph_h_advance(item.codepoint)
-        };
-
-        // we want overlines to be a bit higher
-        item.y_offset = if is_overline { 130 } else { 0 };
-    }
+    // Hand the whole run off to HarfBuzz's own OpenType shaper so ligatures,
+    // kerning, mark attachment and complex scripts survive for every bit of
+    // text we are not overriding. The digit glyphs it produces for the runs we
+    // override are thrown away and replaced by our numerals below.
+    font.shape_with(buf_ref, "ot");
 
+    // Re-read the shaped glyphs and splice our numerals over the digit runs,
+    // leaving the properly shaped glyphs for everything else untouched.
+    let mut buffer = GlyphBuffer::from_ref(buf_ref);
+    let out = splice_numerals(&buffer.glyphs, &runs);
     buffer.glyphs = out;
 
     // Buffer is written back to HB on drop
@@ -63,84 +45,390 @@ fn unicode_codepoint_to_number(unicode: u32) -> Option<u8> {
     }
 }
 
-fn process_digits(digits: &mut Vec<u8>, out: &mut Vec<Glyph>) {
-    // if we had some numbers, and now we're in a non-number now
-    // it means we gotta render the numbers
-    if !digits.is_empty() {
-        // turn digits into a single number...
-        let number = digits_to_number(digits);
-        // ...then turn number to roman numerals string...
-        let roman = number_to_roman_numeral(number);
-        let roman_glyphs = string_to_glyphs(&roman);
-        // ...and add the roman glyphs to the output
-        out.extend_from_slice(&roman_glyphs);
+/// A digit run we are going to override, identified by the cluster range it
+/// occupies in the source text and holding the numeral glyphs that replace it.
+struct NumeralRun {
+    /// first and last source cluster of the run, inclusive
+    start: u32,
+    end: u32,
+    /// the rendered numeral, already mapped to glyph ids and positioned
+    glyphs: Vec<Glyph>,
+}
+
+/// Walks the original unicode buffer and, for every run of consecutive digits
+/// whose active feature asks for conversion, renders the numeral it expands to.
+/// Runs left on a passthrough (off) feature produce no entry, so the real
+/// shaper's digit glyphs stand.
+fn collect_numeral_runs(font: &Font, glyphs: &[Glyph], features: &[Feature]) -> Vec<NumeralRun> {
+    let mut runs = vec![];
+
+    let mut i = 0;
+    while i < glyphs.len() {
+        if unicode_codepoint_to_number(glyphs[i].codepoint).is_none() {
+            i += 1;
+            continue;
+        }
+
+        // gather the whole run of digits
+        let run_start = i;
+        let mut values = vec![];
+        while let Some(number) = glyphs.get(i).and_then(|g| unicode_codepoint_to_number(g.codepoint))
+        {
+            values.push(number);
+            i += 1;
+        }
+        let run = &glyphs[run_start..i];
 
-        digits.clear();
+        // the whole numeral is emitted as a single cluster pointing back at the
+        // first digit's source offset
+        let cluster = run[0].cluster;
+        if let Some(system) = numeral_system_for(cluster, features) {
+            let number = digits_to_number(&values);
+            let numeral = number_to_numeral(number, system);
+            runs.push(NumeralRun {
+                start: cluster,
+                end: run[run.len() - 1].cluster,
+                glyphs: render_numeral_glyphs(font, &numeral, cluster),
+            });
+        }
     }
+
+    runs
 }
 
-fn digits_to_number(digits: &[u8]) -> u64 {
-    digits.iter().rev().enumerate().fold(0, |acc, (idx, num)| {
-        acc + (*num as u64) * 10u64.pow(idx as u32)
-    })
+/// Re-emits the shaped glyphs in order, substituting each overridden digit run
+/// with its numeral glyphs. The numeral is inserted at the first shaped glyph
+/// landing in the run's cluster range, and the remaining digit glyphs of that
+/// run are dropped.
+fn splice_numerals(shaped: &[Glyph], runs: &[NumeralRun]) -> Vec<Glyph> {
+    let mut out = vec![];
+    let mut emitted = vec![false; runs.len()];
+
+    for glyph in shaped {
+        match runs
+            .iter()
+            .position(|run| glyph.cluster >= run.start && glyph.cluster <= run.end)
+        {
+            Some(ix) => {
+                if !emitted[ix] {
+                    out.extend_from_slice(&runs[ix].glyphs);
+                    emitted[ix] = true;
+                }
+            }
+            None => out.push(*glyph),
+        }
+    }
+
+    out
 }
 
-fn string_to_glyphs(string: &str) -> Vec<Glyph> {
-    string
-        .chars()
-        .enumerate()
-        .map(|(ix, x)| Glyph {
-            codepoint: if x == '_' { 0x305 } else { x as u32 },
+/// Turns a numeral string into positioned glyphs: maps each letter to a glyph id
+/// with its advance, and composes a vinculum over every letter marked with a
+/// leading `_`. Every glyph shares the run's `cluster`, so the whole numeral maps
+/// back to those source code units as one cluster (overlines included).
+fn render_numeral_glyphs(font: &Font, numeral: &str, cluster: u32) -> Vec<Glyph> {
+    let mut glyphs = vec![];
+
+    // a leading '_' marks the following letter as carrying a vinculum
+    let mut overlined = false;
+    for ch in numeral.chars() {
+        if ch == '_' {
+            overlined = true;
+            continue;
+        }
+
+        let glyph_id = font.get_glyph(ch as u32, 0);
+        let base = Glyph {
+            codepoint: glyph_id,
             flags: 0,
-            x_advance: 0,
+            x_advance: font.get_glyph_h_advance(glyph_id),
             y_advance: 0,
-            cluster: if x == '_' { ix + 1 } else { ix } as u32,
+            cluster,
             x_offset: 0,
             y_offset: 0,
+        };
+
+        if overlined {
+            let marks = vinculum_marks(font, &base, cluster);
+            glyphs.push(base);
+            glyphs.extend(marks);
+            overlined = false;
+        } else {
+            glyphs.push(base);
+        }
+    }
+
+    glyphs
+}
+
+/// Places a vinculum (overline) over a base glyph from real glyph metrics:
+/// the top of its ink raised a small gap above the top of the base glyph's ink
+/// and tiled across its advance width, rather than a fixed-offset combining char.
+fn vinculum_marks(font: &Font, base: &Glyph, cluster: u32) -> Vec<Glyph> {
+    let overline = font.get_glyph(0x305, 0);
+    let base_extents = font.get_glyph_extents(base.codepoint);
+    let mark_extents = font.get_glyph_extents(overline);
+
+    // put the top of the overline's ink a small, em-scaled gap above the top
+    // of the base's ink (the overline's own thickness hangs below that). The
+    // mark carries its own ink near the top, so subtract its y-bearing rather
+    // than stacking a second cap height on top of the base's.
+    let (_, em) = font.get_scale();
+    let gap = em / 20;
+    let y_offset = base_extents.y_bearing + gap - mark_extents.y_bearing;
+
+    // how much one overline covers; fall back to the base advance when the mark
+    // reports no ink, so we still emit a single centered copy
+    let unit = if mark_extents.width > 0 {
+        mark_extents.width
+    } else {
+        base.x_advance.max(1)
+    };
+    // round the advance up to a whole number of overline copies; both are
+    // non-negative here, so do the ceiling on unsigned to keep it readable
+    let copies = (base.x_advance.max(0) as u32).div_ceil(unit as u32).max(1) as i32;
+    let total = copies * unit;
+
+    // the marks follow a base that has already advanced the pen, so they start
+    // one advance to the left; center the tiled row over the base and undo the
+    // mark's own side bearing so its ink lands where we want it
+    let start = -base.x_advance + (base.x_advance - total) / 2 - mark_extents.x_bearing;
+
+    (0..copies)
+        .map(|i| Glyph {
+            codepoint: overline,
+            flags: 0,
+            // marks must not move the pen; the base already did
+            x_advance: 0,
+            y_advance: 0,
+            cluster,
+            x_offset: start + i * unit,
+            y_offset,
         })
         .collect()
 }
 
-fn number_to_roman_numeral(mut number: u64) -> String {
-    let letters = [
-        (1_000_000, "_M"),
-        (900_000, "_C_M"),
-        (500_000, "_D"),
-        (400_000, "_C_D"),
-        (100_000, "_C"),
-        (90_000, "_X_C"),
-        (50_000, "_L"),
-        (40_000, "_X_L"),
-        (10_000, "_X"),
-        (9_000, "_I_X"),
-        (5_000, "_V"),
-        (4_000, "_I_V"),
-        (1_000, "M"),
-        (900, "CM"),
-        (500, "D"),
-        (400, "CD"),
-        (100, "C"),
-        (90, "XC"),
-        (50, "L"),
-        (40, "XL"),
-        (10, "X"),
-        (9, "IX"),
-        (5, "V"),
-        (4, "IV"),
-        (1, "I"),
-    ];
+fn digits_to_number(digits: &[u8]) -> u64 {
+    digits.iter().rev().enumerate().fold(0, |acc, (idx, num)| {
+        acc + (*num as u64) * 10u64.pow(idx as u32)
+    })
+}
+
+/// A numeral system: a table mapping values to symbols, applied greedily
+/// largest-first, plus a suffix appended once to the finished numeral.
+/// Subtractive cases are encoded as table entries (e.g. the Roman `IX`), so a
+/// new system is just a new table.
+struct NumeralSystem {
+    /// HarfBuzz feature tag that selects this system.
+    tag: u32,
+    table: &'static [(u64, &'static str)],
+    /// appended after the last symbol (e.g. the Greek keraia marking a numeral)
+    suffix: &'static str,
+}
+
+/// Roman numerals, uppercase. Entries prefixed with `_` carry a vinculum
+/// (overline) and stand for the value multiplied by a thousand.
+const ROMAN_UPPER: &[(u64, &str)] = &[
+    (1_000_000, "_M"),
+    (900_000, "_C_M"),
+    (500_000, "_D"),
+    (400_000, "_C_D"),
+    (100_000, "_C"),
+    (90_000, "_X_C"),
+    (50_000, "_L"),
+    (40_000, "_X_L"),
+    (10_000, "_X"),
+    (9_000, "_I_X"),
+    (5_000, "_V"),
+    (4_000, "_I_V"),
+    (1_000, "M"),
+    (900, "CM"),
+    (500, "D"),
+    (400, "CD"),
+    (100, "C"),
+    (90, "XC"),
+    (50, "L"),
+    (40, "XL"),
+    (10, "X"),
+    (9, "IX"),
+    (5, "V"),
+    (4, "IV"),
+    (1, "I"),
+];
+
+/// Roman numerals, lowercase. Same rules as [`ROMAN_UPPER`], minuscule letters.
+const ROMAN_LOWER: &[(u64, &str)] = &[
+    (1_000_000, "_m"),
+    (900_000, "_c_m"),
+    (500_000, "_d"),
+    (400_000, "_c_d"),
+    (100_000, "_c"),
+    (90_000, "_x_c"),
+    (50_000, "_l"),
+    (40_000, "_x_l"),
+    (10_000, "_x"),
+    (9_000, "_i_x"),
+    (5_000, "_v"),
+    (4_000, "_i_v"),
+    (1_000, "m"),
+    (900, "cm"),
+    (500, "d"),
+    (400, "cd"),
+    (100, "c"),
+    (90, "xc"),
+    (50, "l"),
+    (40, "xl"),
+    (10, "x"),
+    (9, "ix"),
+    (5, "v"),
+    (4, "iv"),
+    (1, "i"),
+];
+
+/// Greek alphabetic (Milesian) numerals. Purely additive: thousands take a
+/// lower-left keraia (U+0375) before a unit letter, and the whole numeral ends
+/// in an upper keraia (U+0374, see [`NumeralSystem::suffix`]) to mark it as a
+/// numeral rather than a word.
+const GREEK: &[(u64, &str)] = &[
+    (9_000, "\u{0375}\u{03B8}"),
+    (8_000, "\u{0375}\u{03B7}"),
+    (7_000, "\u{0375}\u{03B6}"),
+    (6_000, "\u{0375}\u{03DB}"),
+    (5_000, "\u{0375}\u{03B5}"),
+    (4_000, "\u{0375}\u{03B4}"),
+    (3_000, "\u{0375}\u{03B3}"),
+    (2_000, "\u{0375}\u{03B2}"),
+    (1_000, "\u{0375}\u{03B1}"),
+    (900, "\u{03E1}"),
+    (800, "\u{03C9}"),
+    (700, "\u{03C8}"),
+    (600, "\u{03C7}"),
+    (500, "\u{03C6}"),
+    (400, "\u{03C5}"),
+    (300, "\u{03C4}"),
+    (200, "\u{03C3}"),
+    (100, "\u{03C1}"),
+    (90, "\u{03DF}"),
+    (80, "\u{03C0}"),
+    (70, "\u{03BF}"),
+    (60, "\u{03BE}"),
+    (50, "\u{03BD}"),
+    (40, "\u{03BC}"),
+    (30, "\u{03BB}"),
+    (20, "\u{03BA}"),
+    (10, "\u{03B9}"),
+    (9, "\u{03B8}"),
+    (8, "\u{03B7}"),
+    (7, "\u{03B6}"),
+    (6, "\u{03DB}"),
+    (5, "\u{03B5}"),
+    (4, "\u{03B4}"),
+    (3, "\u{03B3}"),
+    (2, "\u{03B2}"),
+    (1, "\u{03B1}"),
+];
+
+/// Known numeral systems, keyed by feature tag. The first entry is the default
+/// applied when no feature selects otherwise.
+static SYSTEMS: &[NumeralSystem] = &[
+    NumeralSystem {
+        tag: feature_tag(b"rmn1"),
+        table: ROMAN_UPPER,
+        suffix: "",
+    },
+    NumeralSystem {
+        tag: feature_tag(b"rmnl"),
+        table: ROMAN_LOWER,
+        suffix: "",
+    },
+    NumeralSystem {
+        tag: feature_tag(b"grek"),
+        table: GREEK,
+        suffix: "\u{0374}",
+    },
+];
+
+/// A decoded HarfBuzz `hb_feature_t` record. Field order and widths match the
+/// C struct so the array can be read straight out of wasm memory.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Feature {
+    tag: u32,
+    value: u32,
+    start: u32,
+    end: u32,
+}
+
+/// Packs a four-byte tag the same way HarfBuzz's `HB_TAG` macro does.
+const fn feature_tag(tag: &[u8; 4]) -> u32 {
+    ((tag[0] as u32) << 24)
+        | ((tag[1] as u32) << 16)
+        | ((tag[2] as u32) << 8)
+        | (tag[3] as u32)
+}
+
+/// Reads the `hb_feature_t` array HarfBuzz hands us as a pointer/length pair
+/// into wasm linear memory.
+fn parse_features(ptr: u32, len: u32) -> Vec<Feature> {
+    if ptr == 0 || len == 0 {
+        return vec![];
+    }
+
+    // SAFETY: HarfBuzz guarantees `ptr` points at `len` contiguous
+    // `hb_feature_t` records for the duration of the shape call.
+    let features = unsafe { std::slice::from_raw_parts(ptr as *const Feature, len as usize) };
+    features.to_vec()
+}
+
+/// Picks the numeral system for a digit run, keyed by its first `cluster`. A
+/// digit run is converted atomically — a numeral cannot be half-converted — so
+/// a feature applies to the whole run only if its `[start, end)` range covers
+/// that first cluster. Features are applied in order, last match wins; a value
+/// of `0` turns conversion off. Without a match the default ([`SYSTEMS`]`[0]`,
+/// Roman) applies.
+fn numeral_system_for(cluster: u32, features: &[Feature]) -> Option<&'static NumeralSystem> {
+    let mut system = Some(&SYSTEMS[0]);
+
+    for feature in features {
+        let Some(selected) = SYSTEMS.iter().find(|s| s.tag == feature.tag) else {
+            continue;
+        };
+
+        // honor the feature's [start, end) cluster range
+        if cluster >= feature.start && cluster < feature.end {
+            system = if feature.value == 0 {
+                None
+            } else {
+                Some(selected)
+            };
+        }
+    }
+
+    system
+}
+
+fn number_to_numeral(mut number: u64, system: &NumeralSystem) -> String {
     let mut result = String::new();
 
-    for (value, symbol) in letters {
+    for &(value, symbol) in system.table {
         while number >= value {
             result.push_str(symbol);
             number -= value;
         }
     }
 
+    if !result.is_empty() {
+        result.push_str(system.suffix);
+    }
+
     result
 }
 
+#[cfg(test)]
+fn number_to_roman_numeral(number: u64) -> String {
+    number_to_numeral(number, &SYSTEMS[0])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -176,4 +464,59 @@ mod tests {
 
         assert_eq!("_XMMCCCXXI", number_to_roman_numeral(12321));
     }
+
+    #[test]
+    fn test_number_to_numeral_lowercase() {
+        let lower = &SYSTEMS[1];
+        assert_eq!("i", number_to_numeral(1, lower));
+        assert_eq!("cxxi", number_to_numeral(121, lower));
+        assert_eq!("_xmmcccxxi", number_to_numeral(12321, lower));
+    }
+
+    #[test]
+    fn test_number_to_numeral_greek() {
+        let greek = &SYSTEMS[2];
+        assert_eq!("\u{03B1}\u{0374}", number_to_numeral(1, greek));
+        assert_eq!("\u{03B9}\u{03B1}\u{0374}", number_to_numeral(11, greek));
+        // 2321 = ͵βτκαʹ
+        assert_eq!(
+            "\u{0375}\u{03B2}\u{03C4}\u{03BA}\u{03B1}\u{0374}",
+            number_to_numeral(2321, greek)
+        );
+    }
+
+    #[test]
+    fn test_numeral_system_for() {
+        let global = |tag: &[u8; 4], value: u32| Feature {
+            tag: feature_tag(tag),
+            value,
+            start: 0,
+            end: u32::MAX,
+        };
+
+        // no features => default Roman
+        assert!(std::ptr::eq(
+            numeral_system_for(0, &[]).unwrap(),
+            &SYSTEMS[0]
+        ));
+
+        // a private tag selects its system
+        assert!(std::ptr::eq(
+            numeral_system_for(0, &[global(b"grek", 1)]).unwrap(),
+            &SYSTEMS[2]
+        ));
+
+        // an off value passes digits through unchanged
+        assert!(numeral_system_for(0, &[global(b"rmn1", 0)]).is_none());
+
+        // the range is honored: outside [start, end) the feature is ignored
+        let ranged = Feature {
+            tag: feature_tag(b"rmn1"),
+            value: 0,
+            start: 2,
+            end: 5,
+        };
+        assert!(numeral_system_for(0, &[ranged]).is_some());
+        assert!(numeral_system_for(3, &[ranged]).is_none());
+    }
 }